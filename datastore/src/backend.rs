@@ -0,0 +1,224 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The byte-level storage abstraction that `JsonFileDatastore` is built on.
+//!
+//! `JsonFileDatastore` only ever deals in two kinds of objects, addressed by a string key: the
+//! JSON index (key [`INDEX_KEY`]) and, when the blob sidecar is enabled, one object per
+//! out-of-line value (key `blobs/<digest>`). A [`Backend`] is whatever can store and retrieve
+//! those objects by key. [`LocalFileBackend`] is the default, storing each object as a plain
+//! file; swapping in a different `Backend` (e.g. one backed by a remote object store) changes
+//! nothing about `put`/`get`/`has`/`delete`/`query`.
+
+use error::{DatastoreError, ErrorKind, Resource};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use tempfile::NamedTempFile;
+
+/// Key of the JSON index object within a `Backend`.
+pub const INDEX_KEY: &str = "index";
+
+/// A backend that can store and retrieve opaque byte objects by key.
+///
+/// Keys are simple strings; implementations that map onto a hierarchical namespace (a
+/// filesystem, an S3-style bucket) are free to treat `/` in a key as a path separator, which is
+/// exactly how `blobs/<digest>` keys are meant to be read.
+pub trait Backend: Send + Sync {
+	/// Reads back the object stored under `key`, or `None` if it doesn't exist.
+	fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, DatastoreError>;
+
+	/// Stores `bytes` under `key`, replacing any previous content.
+	fn put_object(&self, key: &str, bytes: &[u8]) -> Result<(), DatastoreError>;
+
+	/// Removes the object stored under `key`. Removing a key that doesn't exist is not an error.
+	fn delete_object(&self, key: &str) -> Result<(), DatastoreError>;
+
+	/// Lists every key currently stored under `prefix`.
+	fn list(&self, prefix: &str) -> Result<Vec<String>, DatastoreError>;
+}
+
+/// The default `Backend`: every object is a plain file inside a single directory, with the JSON
+/// index at a fixed file name and blobs in a `blobs/` subdirectory. Writes go through a
+/// temporary-file-then-rename so that a crash mid-write never corrupts the previous content.
+pub struct LocalFileBackend {
+	dir: PathBuf,
+	index_file_name: OsString,
+}
+
+impl LocalFileBackend {
+	/// Builds a backend rooted at the directory containing `index_path`, using `index_path`'s
+	/// file name for the JSON index object.
+	pub fn new(index_path: PathBuf) -> LocalFileBackend {
+		let index_file_name = index_path
+			.file_name()
+			.map(|name| name.to_os_string())
+			.unwrap_or_else(|| OsString::from("datastore.json"));
+		let dir = index_path.parent().map(|dir| dir.to_path_buf()).unwrap_or_else(|| PathBuf::from("."));
+
+		LocalFileBackend { dir: dir, index_file_name: index_file_name }
+	}
+
+	fn resolve(&self, key: &str) -> PathBuf {
+		if key == INDEX_KEY {
+			self.dir.join(&self.index_file_name)
+		} else {
+			self.dir.join(key)
+		}
+	}
+}
+
+impl Backend for LocalFileBackend {
+	fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, DatastoreError> {
+		let path = self.resolve(key);
+		if !path.exists() {
+			return Ok(None);
+		}
+
+		let mut file = fs::File::open(&path).map_err(|err| {
+			DatastoreError::with_source(Resource::File { path: path.clone() }, ErrorKind::Load, err)
+		})?;
+		let mut bytes = Vec::new();
+		file.read_to_end(&mut bytes).map_err(|err| {
+			DatastoreError::with_source(Resource::File { path: path.clone() }, ErrorKind::Load, err)
+		})?;
+		Ok(Some(bytes))
+	}
+
+	fn put_object(&self, key: &str, bytes: &[u8]) -> Result<(), DatastoreError> {
+		let path = self.resolve(key);
+		let parent = path.parent().ok_or_else(|| {
+			DatastoreError::new(Resource::File { path: path.clone() }, ErrorKind::Persist)
+		})?;
+		fs::create_dir_all(parent).map_err(|err| {
+			DatastoreError::with_source(Resource::File { path: parent.to_path_buf() }, ErrorKind::Persist, err)
+		})?;
+
+		// Create the temporary file in the same directory as the destination, which avoids the
+		// problem of having a file cleaner delete our file while we use it, and guarantees
+		// `persist` below never has to cross filesystems.
+		let mut temporary_file = NamedTempFile::new_in(parent).map_err(|err| {
+			DatastoreError::with_source(Resource::File { path: path.clone() }, ErrorKind::Persist, err)
+		})?;
+		temporary_file.write_all(bytes).map_err(|err| {
+			DatastoreError::with_source(Resource::File { path: path.clone() }, ErrorKind::Persist, err)
+		})?;
+		temporary_file.as_file().sync_data().map_err(|err| {
+			DatastoreError::with_source(Resource::File { path: path.clone() }, ErrorKind::Persist, err)
+		})?;
+		temporary_file.persist(&path).map_err(|err| {
+			DatastoreError::with_source(Resource::File { path: path.clone() }, ErrorKind::Persist, err)
+		})?;
+
+		Ok(())
+	}
+
+	fn delete_object(&self, key: &str) -> Result<(), DatastoreError> {
+		let path = self.resolve(key);
+		match fs::remove_file(&path) {
+			Ok(()) => Ok(()),
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+			Err(err) => Err(DatastoreError::with_source(Resource::File { path: path }, ErrorKind::Flush, err)),
+		}
+	}
+
+	fn list(&self, prefix: &str) -> Result<Vec<String>, DatastoreError> {
+		let dir = self.dir.join(prefix);
+		let entries = match fs::read_dir(&dir) {
+			Ok(entries) => entries,
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(err) => {
+				return Err(DatastoreError::with_source(Resource::File { path: dir }, ErrorKind::Load, err));
+			}
+		};
+
+		let mut keys = Vec::new();
+		for entry in entries {
+			let entry = entry.map_err(|err| {
+				DatastoreError::with_source(Resource::File { path: dir.clone() }, ErrorKind::Load, err)
+			})?;
+			if let Some(name) = entry.file_name().to_str() {
+				keys.push(format!("{}{}", prefix, name));
+			}
+		}
+		Ok(keys)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trips_an_object() {
+		let dir = ::tempfile::tempdir().unwrap();
+		let backend = LocalFileBackend::new(dir.path().join("store.json"));
+
+		assert_eq!(backend.get_object("blobs/abc").unwrap(), None);
+
+		backend.put_object("blobs/abc", b"hello").unwrap();
+		assert_eq!(backend.get_object("blobs/abc").unwrap(), Some(b"hello".to_vec()));
+
+		backend.put_object("blobs/abc", b"world").unwrap();
+		assert_eq!(backend.get_object("blobs/abc").unwrap(), Some(b"world".to_vec()));
+
+		backend.delete_object("blobs/abc").unwrap();
+		assert_eq!(backend.get_object("blobs/abc").unwrap(), None);
+
+		// Deleting an already-absent key is not an error.
+		backend.delete_object("blobs/abc").unwrap();
+	}
+
+	#[test]
+	fn list_returns_keys_under_prefix_and_empty_for_missing_dir() {
+		let dir = ::tempfile::tempdir().unwrap();
+		let backend = LocalFileBackend::new(dir.path().join("store.json"));
+
+		assert_eq!(backend.list("blobs/").unwrap(), Vec::<String>::new());
+
+		backend.put_object("blobs/one", b"1").unwrap();
+		backend.put_object("blobs/two", b"2").unwrap();
+
+		let mut keys = backend.list("blobs/").unwrap();
+		keys.sort();
+		assert_eq!(keys, vec!["blobs/one".to_owned(), "blobs/two".to_owned()]);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn list_silently_skips_non_utf8_file_names() {
+		use std::ffi::OsStr;
+		use std::os::unix::ffi::OsStrExt;
+
+		let dir = ::tempfile::tempdir().unwrap();
+		let backend = LocalFileBackend::new(dir.path().join("store.json"));
+
+		backend.put_object("blobs/valid", b"1").unwrap();
+
+		let blobs_dir = dir.path().join("blobs");
+		let bogus_name = OsStr::from_bytes(&[0x66, 0x6f, 0xff, 0x6f]);
+		fs::write(blobs_dir.join(bogus_name), b"2").unwrap();
+
+		let keys = backend.list("blobs/").unwrap();
+		assert_eq!(keys, vec!["blobs/valid".to_owned()]);
+	}
+}
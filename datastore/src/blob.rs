@@ -0,0 +1,79 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Content-addressed sidecar storage for values that are too large to keep inline in the index.
+//!
+//! Blobs are stored through the same [`Backend`] as the index itself, under the `blobs/` prefix,
+//! so this module works unchanged whether the datastore is backed by local files or a remote
+//! object store.
+
+use backend::Backend;
+use error::{DatastoreError, ErrorKind, Resource};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// Name of the key, inside a JSON object, that marks it as a reference to a blob rather than an
+/// inline value.
+pub const BLOB_MARKER_KEY: &str = "$blob";
+/// Name of the key carrying the length (in bytes) of the referenced blob.
+pub const BLOB_LEN_KEY: &str = "len";
+
+/// Prefix that every blob object key is stored under.
+const BLOBS_PREFIX: &str = "blobs/";
+
+fn object_key(digest: &str) -> String {
+	format!("{}{}", BLOBS_PREFIX, digest)
+}
+
+/// Hex-encodes the SHA-256 digest of `bytes`.
+pub fn digest_hex(bytes: &[u8]) -> String {
+	let digest = Sha256::digest(bytes);
+	digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Writes `bytes` to the blob sidecar for `digest`. If a blob with this digest already exists
+/// (checked by reading it back through the backend), nothing is written: the content is known to
+/// be identical since the digest is content-addressed.
+pub fn write(backend: &Backend, digest: &str, bytes: &[u8]) -> Result<(), DatastoreError> {
+	let key = object_key(digest);
+	if backend.get_object(&key)?.is_some() {
+		return Ok(());
+	}
+	backend.put_object(&key, bytes)
+}
+
+/// Reads back the blob stored under `digest`.
+pub fn read(backend: &Backend, digest: &str) -> Result<Vec<u8>, DatastoreError> {
+	let key = object_key(digest);
+	backend
+		.get_object(&key)?
+		.ok_or_else(|| DatastoreError::new(Resource::Key { key: key.clone() }, ErrorKind::Load))
+}
+
+/// Removes every blob whose digest is not present in `referenced`.
+pub fn collect_garbage(backend: &Backend, referenced: &HashSet<String>) -> Result<(), DatastoreError> {
+	for key in backend.list(BLOBS_PREFIX)? {
+		let digest = &key[BLOBS_PREFIX.len()..];
+		if !referenced.contains(digest) {
+			backend.delete_object(&key)?;
+		}
+	}
+	Ok(())
+}
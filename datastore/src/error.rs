@@ -0,0 +1,154 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::Error as IoError;
+use std::path::PathBuf;
+
+/// The resource that a `DatastoreError` was produced while operating on.
+#[derive(Debug)]
+pub enum Resource {
+	/// The datastore as a whole (e.g. its top-level file handle).
+	Manager,
+	/// A specific file on disk.
+	File {
+		/// Path of the file that the error relates to.
+		path: PathBuf,
+	},
+	/// A single key within the datastore.
+	Key {
+		/// The key that the error relates to.
+		key: String,
+	},
+}
+
+impl fmt::Display for Resource {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Resource::Manager => write!(f, "datastore"),
+			Resource::File { ref path } => write!(f, "file {}", path.display()),
+			Resource::Key { ref key } => write!(f, "key {:?}", key),
+		}
+	}
+}
+
+/// The kind of operation that failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+	/// Loading the on-disk content of the datastore failed.
+	Load,
+	/// Flushing the in-memory content to disk failed.
+	Flush,
+	/// Parsing a value (the whole file, or a single entry) as JSON failed.
+	Parse,
+	/// Persisting the temporary file to its final destination failed.
+	Persist,
+	/// A file or one of its ancestor directories is more permissive, or owned by a different
+	/// user, than `Mistrust` allows.
+	Permission,
+	/// The datastore's configured `Quota` was exceeded and no entry was evictable to make room.
+	Quota,
+}
+
+impl fmt::Display for ErrorKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let s = match *self {
+			ErrorKind::Load => "failed to load",
+			ErrorKind::Flush => "failed to flush",
+			ErrorKind::Parse => "failed to parse",
+			ErrorKind::Persist => "failed to persist",
+			ErrorKind::Permission => "permission too permissive",
+			ErrorKind::Quota => "quota exceeded",
+		};
+		f.write_str(s)
+	}
+}
+
+/// Error that can be produced by `JsonFileDatastore`.
+///
+/// Unlike a plain `std::io::Error`, this names both the resource (file, key, ...) and the kind of
+/// operation that failed, and keeps the underlying error as its `source()` so that callers can
+/// decide, for instance, to skip a single corrupt key rather than aborting the whole load.
+#[derive(Debug)]
+pub struct DatastoreError {
+	resource: Resource,
+	kind: ErrorKind,
+	source: Option<Box<StdError + Send + Sync>>,
+}
+
+impl DatastoreError {
+	/// Builds a new `DatastoreError` with no underlying source error.
+	pub fn new(resource: Resource, kind: ErrorKind) -> DatastoreError {
+		DatastoreError {
+			resource: resource,
+			kind: kind,
+			source: None,
+		}
+	}
+
+	/// Builds a new `DatastoreError`, attaching the error that caused it.
+	pub fn with_source<E>(resource: Resource, kind: ErrorKind, source: E) -> DatastoreError
+		where E: Into<Box<StdError + Send + Sync>>
+	{
+		DatastoreError {
+			resource: resource,
+			kind: kind,
+			source: Some(source.into()),
+		}
+	}
+
+	/// Returns the resource that the error relates to.
+	pub fn resource(&self) -> &Resource {
+		&self.resource
+	}
+
+	/// Returns the kind of operation that failed.
+	pub fn kind(&self) -> ErrorKind {
+		self.kind
+	}
+}
+
+impl fmt::Display for DatastoreError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} ({})", self.kind, self.resource)?;
+		if let Some(ref source) = self.source {
+			write!(f, ": {}", source)?;
+		}
+		Ok(())
+	}
+}
+
+impl StdError for DatastoreError {
+	fn description(&self) -> &str {
+		"error in JsonFileDatastore"
+	}
+
+	fn cause(&self) -> Option<&StdError> {
+		self.source.as_ref().map(|e| &**e as &StdError)
+	}
+}
+
+impl From<DatastoreError> for IoError {
+	/// Converts into an opaque `io::Error`, for interop with code that still expects it.
+	fn from(err: DatastoreError) -> IoError {
+		IoError::new(::std::io::ErrorKind::Other, err.to_string())
+	}
+}
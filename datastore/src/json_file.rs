@@ -19,30 +19,120 @@
 // DEALINGS IN THE SOFTWARE.
 
 use Datastore;
-use futures::Future;
+use backend::{Backend, INDEX_KEY, LocalFileBackend};
+use blob;
+use error::{DatastoreError, ErrorKind, Resource};
 use futures::stream::{Stream, iter_ok};
+use mistrust::Mistrust;
 use parking_lot::Mutex;
 use query::{Query, naive_apply_query};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use serde_json::{Map, from_value, to_value, from_reader, to_writer};
+use serde_json::{Map, from_slice, from_value, to_value, to_vec};
 use serde_json::value::Value;
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::fs;
-use std::io::Cursor;
-use std::io::Error as IoError;
-use std::io::ErrorKind as IoErrorKind;
-use std::io::Read;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use tempfile::NamedTempFile;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-/// Implementation of `Datastore` that uses a single plain JSON file.
+/// If `value` is a blob reference marker (`{ "$blob": "<digest>", "len": N }`), returns its
+/// digest.
+fn as_blob_ref(value: &Value) -> Option<String> {
+	let object = match *value {
+		Value::Object(ref object) => object,
+		_ => return None,
+	};
+	if object.len() != 2 {
+		return None;
+	}
+	match (object.get(blob::BLOB_MARKER_KEY), object.get(blob::BLOB_LEN_KEY)) {
+		(Some(&Value::String(ref digest)), Some(&Value::Number(_))) => Some(digest.clone()),
+		_ => None,
+	}
+}
+
+/// If `value` is a TTL wrapper (`{ "value": ..., "expires_at": <unix_secs> }`), splits it into
+/// the inner value and the expiry timestamp. Plain values (the common case) are passed through
+/// unchanged with no expiry, which keeps the on-disk format backward-compatible.
+fn split_ttl_wrapper(value: Value) -> (Value, Option<u64>) {
+	let is_wrapper = match value {
+		Value::Object(ref object) => {
+			object.len() == 2 && object.get("value").is_some() &&
+				object.get("expires_at").map_or(false, Value::is_u64)
+		}
+		_ => false,
+	};
+	if !is_wrapper {
+		return (value, None);
+	}
+	match value {
+		Value::Object(mut object) => {
+			let expires_at = object.remove("expires_at").and_then(|v| v.as_u64());
+			let inner = object.remove("value").expect("checked above");
+			(inner, expires_at)
+		}
+		_ => unreachable!("checked above"),
+	}
+}
+
+/// Returns the current time as a Unix timestamp, in seconds.
+fn now_unix_secs() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+/// A value together with its optional expiry and its approximate serialized size, used for
+/// quota accounting.
+struct Entry<T> {
+	value: T,
+	expires_at: Option<u64>,
+	size: usize,
+}
+
+impl<T: Clone> Clone for Entry<T> {
+	fn clone(&self) -> Entry<T> {
+		Entry {
+			value: self.value.clone(),
+			expires_at: self.expires_at,
+			size: self.size,
+		}
+	}
+}
+
+impl<T> Entry<T> {
+	fn is_expired(&self) -> bool {
+		self.expires_at.map_or(false, |expiry| expiry <= now_unix_secs())
+	}
+}
+
+/// Caps on the total size of a `JsonFileDatastore`. `put`/`put_with_ttl` evict the
+/// soonest-to-expire entries to make room for a new one; if nothing is evictable (every existing
+/// entry is non-expiring) and the datastore is still over quota, the insertion is rejected.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Quota {
+	/// Maximum number of entries the datastore may hold at once.
+	pub max_entries: Option<usize>,
+	/// Maximum total size, in bytes, of all entries' serialized values.
+	pub max_bytes: Option<usize>,
+}
+
+/// Implementation of `Datastore` backed by a JSON index and, optionally, a blob sidecar, both
+/// read and written through a [`Backend`]. By default that backend is [`LocalFileBackend`] (a
+/// single plain JSON file plus a `blobs/` directory), but [`JsonFileDatastore::with_backend`]
+/// accepts any `Backend`, which is what lets the same `put`/`get`/`has`/`delete`/`query` code run
+/// against a remote object store instead.
 pub struct JsonFileDatastore<T>
 	where T: Serialize + DeserializeOwned
 {
-	path: PathBuf,
-	content: Mutex<HashMap<String, T>>,
+	backend: Box<Backend>,
+	content: Mutex<HashMap<String, Entry<T>>>,
+	/// Values whose serialized size in bytes exceeds this are written to a content-addressed
+	/// blob file instead of inline in the index. `None` disables the sidecar entirely.
+	blob_threshold: Option<usize>,
+	quota: Quota,
+	total_bytes: Mutex<usize>,
 }
 
 impl<T> JsonFileDatastore<T>
@@ -51,87 +141,285 @@ impl<T> JsonFileDatastore<T>
 	/// Opens or creates the datastore. If the path refers to an existing path, then this function
 	/// will attempt to load an existing set of values from it (which can result in an error).
 	/// Otherwise if the path doesn't exist, a new empty datastore will be created.
-	pub fn new<P>(path: P) -> Result<JsonFileDatastore<T>, IoError>
+	pub fn new<P>(path: P) -> Result<JsonFileDatastore<T>, DatastoreError>
+		where P: Into<PathBuf>
+	{
+		JsonFileDatastore::with_backend(Box::new(LocalFileBackend::new(path.into())), None, Quota::default())
+	}
+
+	/// Like [`new`](JsonFileDatastore::new), but first walks the path and its ancestor
+	/// directories and refuses to load if the file or its parent directory is group- or
+	/// world-accessible, if any directory further up is group- or world-writable, or if any of
+	/// them is owned by a user other than the current one or root.
+	///
+	/// This is meant for datastores that may hold peer state or key material: loading such a
+	/// file from a location another local user could have tampered with would silently trust
+	/// their content. Checks can be relaxed (e.g. for CI containers running as root with a loose
+	/// umask) via [`Mistrust::ignore_checks`] or the `DATASTORE_TRUST_PERMISSIONS=1` environment
+	/// variable.
+	pub fn with_permission_checks<P>(
+		path: P,
+		mistrust: Mistrust,
+	) -> Result<JsonFileDatastore<T>, DatastoreError>
 		where P: Into<PathBuf>
 	{
 		let path = path.into();
+		mistrust.check_path(&path)?;
+		JsonFileDatastore::with_backend(Box::new(LocalFileBackend::new(path)), None, Quota::default())
+	}
 
-		if !path.exists() {
-			return Ok(JsonFileDatastore {
-				path: path,
-				content: Mutex::new(HashMap::new()),
-			});
-		}
+	/// Like [`new`](JsonFileDatastore::new), but values whose serialized size exceeds
+	/// `blob_threshold` bytes are written out-of-line into `<dir>/blobs/<sha256-of-value>`
+	/// instead of inline in the index, so that `flush()` doesn't have to rewrite large payloads
+	/// that haven't changed.
+	///
+	/// A blob reference is recognized purely by shape (a two-key object `{"$blob": ..., "len":
+	/// ...}`), so `T` must not serialize to that exact shape itself, or it will be misread as a
+	/// blob reference on the next load.
+	pub fn with_blob_threshold<P>(
+		path: P,
+		blob_threshold: usize,
+	) -> Result<JsonFileDatastore<T>, DatastoreError>
+		where P: Into<PathBuf>
+	{
+		JsonFileDatastore::with_backend(
+			Box::new(LocalFileBackend::new(path.into())),
+			Some(blob_threshold),
+			Quota::default(),
+		)
+	}
 
-		let content = {
-			let mut file = fs::File::open(&path)?;
+	/// Like [`new`](JsonFileDatastore::new), but caps the datastore's total entry count and/or
+	/// total value size to `quota`. Once the cap is reached, `put`/`put_with_ttl` evict the
+	/// soonest-to-expire entries to make room.
+	pub fn with_quota<P>(path: P, quota: Quota) -> Result<JsonFileDatastore<T>, DatastoreError>
+		where P: Into<PathBuf>
+	{
+		JsonFileDatastore::with_backend(Box::new(LocalFileBackend::new(path.into())), None, quota)
+	}
 
-			// We want to support empty files (and treat them as an empty recordset). Unfortunately
-			// `serde_json` will always produce an error if we do this ("unexpected EOF at line 0
-			// column 0"). Therefore we start by reading one byte from the file in order to check
-			// for EOF.
+	/// Opens or creates the datastore directly against an arbitrary [`Backend`] — a local
+	/// directory ([`LocalFileBackend`], used internally by `new` and friends), a remote object
+	/// store (`object_store::RemoteBackend`), or any other implementation. This is what lets the
+	/// exact same `put`/`get`/`has`/`delete`/`query` code run against a JSON file in tests and a
+	/// bucket in production, switched purely through configuration.
+	///
+	/// If the backend already has an index object, this attempts to load it (which can result in
+	/// an error). Otherwise a new empty datastore is created.
+	pub fn with_backend(
+		backend: Box<Backend>,
+		blob_threshold: Option<usize>,
+		quota: Quota,
+	) -> Result<JsonFileDatastore<T>, DatastoreError> {
+		let index_bytes = backend.get_object(INDEX_KEY)?;
 
-			let mut first_byte = [0];
-			if file.read(&mut first_byte)? == 0 {
-				// File is empty.
-				HashMap::new()
-			} else {
-				match from_reader::<_, Value>(Cursor::new(first_byte).chain(file)) {
-					Ok(Value::Null) => HashMap::new(),
+		let (content, total_bytes) = match index_bytes {
+			None => (HashMap::new(), 0),
+			Some(ref bytes) if bytes.is_empty() => (HashMap::new(), 0),
+			Some(bytes) => {
+				match from_slice::<Value>(&bytes) {
+					Ok(Value::Null) => (HashMap::new(), 0),
 					Ok(Value::Object(map)) => {
 						let mut out = HashMap::with_capacity(map.len());
+						let mut total_bytes = 0;
 						for (key, value) in map.into_iter() {
-							let value = match from_value(value) {
-								Ok(v) => v,
-								Err(err) => return Err(IoError::new(IoErrorKind::InvalidData, err)),
+							let (value, expires_at) = split_ttl_wrapper(value);
+
+							// Entries that already expired while we weren't looking are simply
+							// dropped, as if they had never been put in the first place.
+							if expires_at.map_or(false, |expiry| expiry <= now_unix_secs()) {
+								continue;
+							}
+
+							let value: T = if let Some(digest) = as_blob_ref(&value) {
+								let bytes = blob::read(&*backend, &digest)?;
+								match from_slice(&bytes) {
+									Ok(v) => v,
+									Err(err) => {
+										return Err(DatastoreError::with_source(
+											Resource::Key { key: key.clone() },
+											ErrorKind::Parse,
+											err,
+										));
+									}
+								}
+							} else {
+								match from_value(value) {
+									Ok(v) => v,
+									Err(err) => {
+										return Err(DatastoreError::with_source(
+											Resource::Key { key: key.clone() },
+											ErrorKind::Parse,
+											err,
+										));
+									}
+								}
 							};
-							out.insert(key, value);
+
+							let size = to_vec(&value).map(|b| b.len()).unwrap_or(0);
+							total_bytes += size;
+							out.insert(
+								key,
+								Entry { value: value, expires_at: expires_at, size: size },
+							);
 						}
-						out
+						(out, total_bytes)
 					}
 					Ok(_) => {
-						return Err(IoError::new(IoErrorKind::InvalidData, "expected JSON object"));
+						return Err(DatastoreError::new(Resource::Key { key: INDEX_KEY.to_owned() }, ErrorKind::Parse));
 					}
 					Err(err) => {
-						return Err(IoError::new(IoErrorKind::InvalidData, err));
+						return Err(DatastoreError::with_source(
+							Resource::Key { key: INDEX_KEY.to_owned() },
+							ErrorKind::Parse,
+							err,
+						));
 					}
 				}
 			}
 		};
 
 		Ok(JsonFileDatastore {
-			path: path,
+			backend: backend,
 			content: Mutex::new(content),
+			blob_threshold: blob_threshold,
+			quota: quota,
+			total_bytes: Mutex::new(total_bytes),
 		})
 	}
 
+	/// Inserts `value` under `key`, so that it expires `ttl` from now. `get`/`has`/`query` treat
+	/// an expired entry as absent, and `flush()` prunes it from disk.
+	///
+	/// Fails if the datastore is at its `Quota` and no expiring entry is available to evict to
+	/// make room.
+	///
+	/// A TTL wrapper is recognized purely by shape (a two-key object `{"value": ..., "expires_at":
+	/// ...}`), so `T` must not serialize to that exact shape itself, or it will be misread as a
+	/// TTL wrapper on the next load.
+	pub fn put_with_ttl(&self, key: Cow<str>, value: T, ttl: Duration) -> Result<(), DatastoreError>
+		where T: Clone + Ord
+	{
+		// Round up to the next whole second so a sub-second TTL (e.g. 500ms) doesn't truncate to
+		// zero and make the entry expired the instant it's inserted.
+		let ttl_secs = if ttl.subsec_nanos() == 0 { ttl.as_secs() } else { ttl.as_secs() + 1 };
+		let expires_at = now_unix_secs() + ttl_secs;
+		self.insert(key.into_owned(), value, Some(expires_at))
+	}
+
+	fn insert(&self, key: String, value: T, expires_at: Option<u64>) -> Result<(), DatastoreError>
+		where T: Clone + Ord
+	{
+		let size = to_vec(&value).map(|b| b.len()).unwrap_or(0);
+
+		let mut content = self.content.lock();
+		let mut total_bytes = self.total_bytes.lock();
+
+		// The replaced key's old bytes are still part of `total_bytes` until the replacement is
+		// actually committed below, so quota checks below must account for them separately rather
+		// than subtracting them up front — otherwise a failed replacement (nothing evictable)
+		// would leave `total_bytes` permanently short by `old.size`.
+		let old_size = content.get(&key).map(|old| old.size).unwrap_or(0);
+
+		loop {
+			let over_count = self.quota
+				.max_entries
+				.map_or(false, |max| !content.contains_key(&key) && content.len() >= max);
+			let over_bytes = self.quota
+				.max_bytes
+				.map_or(false, |max| *total_bytes - old_size + size > max);
+			if !over_count && !over_bytes {
+				break;
+			}
+
+			let victim = content
+				.iter()
+				.filter(|&(k, entry)| *k != key && entry.expires_at.is_some())
+				.min_by_key(|&(_, entry)| entry.expires_at.unwrap())
+				.map(|(k, _)| k.clone());
+
+			match victim {
+				Some(victim_key) => {
+					let removed = content.remove(&victim_key).expect("key was just found");
+					*total_bytes -= removed.size;
+				}
+				None => {
+					return Err(DatastoreError::new(Resource::Key { key: key }, ErrorKind::Quota));
+				}
+			}
+		}
+
+		*total_bytes = *total_bytes - old_size + size;
+		content.insert(key, Entry { value: value, expires_at: expires_at, size: size });
+		Ok(())
+	}
+
 	/// Flushes the content of the datastore to the disk.
 	///
 	/// This function can only fail in case of a disk access error. If an error occurs, any change
 	/// to the datastore that was performed since the last successful flush will be lost. No data
 	/// will be corrupted.
-	pub fn flush(&self) -> Result<(), IoError> {
-		// Create a temporary file in the same directory as the destination, which avoids the
-		// problem of having a file cleaner delete our file while we use it.
-		let self_path_parent = self.path
-								   .parent()
-								   .ok_or(IoError::new(
-			IoErrorKind::Other,
-			"couldn't get parent directory of destination",
-		))?;
-		let mut temporary_file = NamedTempFile::new_in(self_path_parent)?;
+	pub fn flush(&self) -> Result<(), DatastoreError> {
+		let mut content = self.content.lock();
+		let mut total_bytes = self.total_bytes.lock();
+
+		// Prune expired entries before persisting, so a long-running node doesn't grow the file
+		// with dead data.
+		content.retain(|_, entry| {
+			if entry.is_expired() {
+				*total_bytes -= entry.size;
+				false
+			} else {
+				true
+			}
+		});
+
+		let mut referenced_blobs = HashSet::new();
+		let mut index = Map::new();
+		for (key, entry) in content.iter() {
+			let value = to_value(&entry.value).map_err(|err| {
+				DatastoreError::with_source(Resource::Key { key: key.clone() }, ErrorKind::Flush, err)
+			})?;
+			let value = match self.blob_threshold {
+				Some(threshold) => {
+					let bytes = to_vec(&value).map_err(|err| {
+						DatastoreError::with_source(Resource::Key { key: key.clone() }, ErrorKind::Flush, err)
+					})?;
+					if bytes.len() > threshold {
+						let digest = blob::digest_hex(&bytes);
+						blob::write(&*self.backend, &digest, &bytes)?;
+						let mut marker = Map::new();
+						marker.insert(blob::BLOB_MARKER_KEY.to_owned(), Value::String(digest.clone()));
+						marker.insert(blob::BLOB_LEN_KEY.to_owned(), Value::from(bytes.len() as u64));
+						referenced_blobs.insert(digest);
+						Value::Object(marker)
+					} else {
+						value
+					}
+				}
+				None => value,
+			};
+			let indexed = match entry.expires_at {
+				Some(expires_at) => {
+					let mut wrapper = Map::new();
+					wrapper.insert("value".to_owned(), value);
+					wrapper.insert("expires_at".to_owned(), Value::from(expires_at));
+					Value::Object(wrapper)
+				}
+				None => value,
+			};
+			index.insert(key.clone(), indexed);
+		}
+
+		let bytes = to_vec(&index).map_err(|err| {
+			DatastoreError::with_source(Resource::Key { key: INDEX_KEY.to_owned() }, ErrorKind::Flush, err)
+		})?;
+		self.backend.put_object(INDEX_KEY, &bytes)?;
+
+		if self.blob_threshold.is_some() {
+			blob::collect_garbage(&*self.backend, &referenced_blobs)?;
+		}
 
-		let content = self.content.lock();
-		to_writer(
-			&mut temporary_file,
-			&content.iter().map(|(k, v)| (k.clone(), to_value(v).unwrap())).collect::<Map<_, _>>(),
-		)?; // TODO: panic!
-		temporary_file.sync_data()?;
-
-		// Note that `persist` will fail if we try to persist across filesystems. However that
-		// shouldn't happen since we created the temporary file in the same directory as the final
-		// path.
-		temporary_file.persist(&self.path)?;
 		Ok(())
 	}
 }
@@ -141,50 +429,62 @@ impl<T> Datastore<T> for JsonFileDatastore<T>
 {
 	#[inline]
 	fn put(&self, key: Cow<str>, value: T) {
-		let mut content = self.content.lock();
-		content.insert(key.into_owned(), value);
+		// Nothing sensible to do with a quota rejection here, since this trait method has no way
+		// to report it back to the caller; see `put_with_ttl` for the fallible equivalent.
+		let _ = self.insert(key.into_owned(), value, None);
 	}
 
 	fn get(&self, key: &str) -> Option<T> {
 		let content = self.content.lock();
-		// If the JSON is malformed, we just ignore the value.
-		content.get(key).cloned()
+		content.get(key).filter(|entry| !entry.is_expired()).map(|entry| entry.value.clone())
 	}
 
 	fn has(&self, key: &str) -> bool {
 		let content = self.content.lock();
-		content.contains_key(key)
+		content.get(key).map_or(false, |entry| !entry.is_expired())
 	}
 
 	fn delete(&self, key: &str) -> bool {
 		let mut content = self.content.lock();
-		content.remove(key).is_some()
+		match content.remove(key) {
+			Some(entry) => {
+				*self.total_bytes.lock() -= entry.size;
+				true
+			}
+			None => false,
+		}
 	}
 
 	fn query<'a>(
 		&'a self,
 		query: Query<T>,
-	) -> Box<Stream<Item = (String, T), Error = IoError> + 'a> {
-		let content = self.content.lock();
-
+	) -> Box<Stream<Item = (String, T), Error = DatastoreError> + 'a> {
 		let keys_only = query.keys_only;
 
-		let content_stream = iter_ok(content.iter().filter_map(|(key, value)| {
-			// Skip values that are malformed.
-			let value = if keys_only { Default::default() } else { value.clone() };
+		// Snapshot just the keys that can possibly match, then release the lock immediately.
+		// Cloning every value up front (or worse, holding the lock for the whole scan) would
+		// block `put`/`get`/`delete` for as long as the query takes to run.
+		let matching_keys: Vec<String> = {
+			let content = self.content.lock();
+			content
+				.iter()
+				.filter(|&(key, entry)| key.starts_with(&*query.prefix) && !entry.is_expired())
+				.map(|(key, _)| key.clone())
+				.collect()
+		};
 
-			Some((key.clone(), value))
-		}));
+		// Each value is only cloned out of the `Mutex` as the stream is actually driven, one key
+		// at a time, so a huge result set is never materialized all at once. `keys_only` skips
+		// the value clone entirely.
+		let content_stream = iter_ok(matching_keys).filter_map(move |key| {
+			let content = self.content.lock();
+			let value = content.get(&key).filter(|entry| !entry.is_expired()).map(|entry| {
+				if keys_only { Default::default() } else { entry.value.clone() }
+			});
+			value.map(|value| (key, value))
+		});
 
-		// `content_stream` reads from the content of the `Mutex`, so we need to clone the data
-		// into a `Vec` before returning.
-		let collected = naive_apply_query(content_stream, query)
-			.collect()
-			.wait()
-			.expect("can only fail if either `naive_apply_query` or `content_stream` produce \
-					 an error, which cann't happen");
-		let output_stream = iter_ok(collected.into_iter());
-		Box::new(output_stream) as Box<_>
+		Box::new(naive_apply_query(content_stream, query)) as Box<_>
 	}
 }
 
@@ -208,7 +508,9 @@ mod tests {
 	use {Query, Order, Filter, FilterTy, FilterOp};
 	use Datastore;
 	use JsonFileDatastore;
+	use Mistrust;
 	use futures::{Future, Stream};
+	use std::fs;
 	use tempfile::NamedTempFile;
 
 	#[test]
@@ -268,4 +570,207 @@ mod tests {
 		assert_eq!(query[1].0, "foo3");
 		assert_eq!(query[1].1, &[7, 8, 9]);
 	}
+
+	#[test]
+	fn corrupt_key_reports_its_own_key() {
+		use error::{ErrorKind, Resource};
+		use std::io::Write;
+
+		let temp_file = NamedTempFile::new().unwrap();
+		write!(temp_file.as_file(), r#"{{"foo": [1, 2, 3], "bar": "not an array"}}"#).unwrap();
+
+		let err = JsonFileDatastore::<Vec<u8>>::new(temp_file.path()).unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::Parse);
+		match *err.resource() {
+			Resource::Key { ref key } => assert_eq!(key, "bar"),
+			ref other => panic!("expected Resource::Key, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn flush_reports_unserializable_value_as_flush_error_instead_of_panicking() {
+		use error::ErrorKind;
+		use std::collections::BTreeMap;
+
+		let temp_file = NamedTempFile::new().unwrap();
+		let datastore = JsonFileDatastore::<BTreeMap<(i32, i32), i32>>::new(temp_file.path()).unwrap();
+
+		// Tuple map keys satisfy `Serialize`, but `serde_json` can't represent them as a JSON
+		// object key, so this must surface as a `Flush` error rather than panicking on `unwrap`.
+		let mut value = BTreeMap::new();
+		value.insert((1, 2), 3);
+		datastore.put("bad".into(), value);
+
+		let err = datastore.flush().unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::Flush);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn permission_checks_reject_world_readable_file() {
+		use error::ErrorKind;
+		use std::fs::Permissions;
+		use std::os::unix::fs::PermissionsExt;
+
+		let temp_file = NamedTempFile::new().unwrap();
+		fs::set_permissions(temp_file.path(), Permissions::from_mode(0o644)).unwrap();
+
+		let err = JsonFileDatastore::<Vec<u8>>::with_permission_checks(
+			temp_file.path(),
+			Mistrust::new(),
+		).unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::Permission);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn permission_checks_can_be_ignored() {
+		use std::fs::Permissions;
+		use std::os::unix::fs::PermissionsExt;
+
+		let temp_file = NamedTempFile::new().unwrap();
+		fs::set_permissions(temp_file.path(), Permissions::from_mode(0o644)).unwrap();
+
+		JsonFileDatastore::<Vec<u8>>::with_permission_checks(
+			temp_file.path(),
+			Mistrust::ignore_checks(),
+		).unwrap();
+	}
+
+	#[test]
+	fn blob_threshold_moves_large_values_out_of_line() {
+		let dir = ::tempfile::tempdir().unwrap();
+		let store_path = dir.path().join("store.json");
+
+		let datastore = JsonFileDatastore::<Vec<u8>>::with_blob_threshold(&store_path, 8).unwrap();
+		datastore.put("small".into(), vec![1, 2, 3]);
+		datastore.put("big".into(), vec![0u8; 128]);
+		datastore.flush().unwrap();
+
+		let index = fs::read_to_string(&store_path).unwrap();
+		assert!(index.contains("$blob"));
+
+		let blobs_dir = dir.path().join("blobs");
+		assert_eq!(fs::read_dir(&blobs_dir).unwrap().count(), 1);
+
+		drop(datastore);
+
+		let reload = JsonFileDatastore::<Vec<u8>>::with_blob_threshold(&store_path, 8).unwrap();
+		assert_eq!(reload.get("small").unwrap(), &[1, 2, 3]);
+		assert_eq!(reload.get("big").unwrap(), vec![0u8; 128]);
+	}
+
+	#[test]
+	fn blob_threshold_garbage_collects_unreferenced_blobs() {
+		let dir = ::tempfile::tempdir().unwrap();
+		let store_path = dir.path().join("store.json");
+		let blobs_dir = dir.path().join("blobs");
+
+		let datastore = JsonFileDatastore::<Vec<u8>>::with_blob_threshold(&store_path, 8).unwrap();
+		datastore.put("big".into(), vec![0u8; 128]);
+		datastore.flush().unwrap();
+		assert_eq!(fs::read_dir(&blobs_dir).unwrap().count(), 1);
+
+		datastore.delete("big");
+		datastore.flush().unwrap();
+		assert_eq!(fs::read_dir(&blobs_dir).unwrap().count(), 0);
+	}
+
+	#[test]
+	fn expired_entry_is_treated_as_absent_and_pruned_on_flush() {
+		use std::time::Duration;
+
+		let temp_file = NamedTempFile::new().unwrap();
+
+		let datastore = JsonFileDatastore::<Vec<u8>>::new(temp_file.path()).unwrap();
+		datastore.put_with_ttl("gone".into(), vec![1], Duration::from_secs(0)).unwrap();
+		datastore.put("stays".into(), vec![2]);
+
+		assert_eq!(datastore.has("gone"), false);
+		assert_eq!(datastore.get("gone"), None);
+
+		datastore.flush().unwrap();
+		drop(datastore);
+
+		let reload = JsonFileDatastore::<Vec<u8>>::new(temp_file.path()).unwrap();
+		assert_eq!(reload.has("gone"), false);
+		assert_eq!(reload.get("stays").unwrap(), &[2]);
+	}
+
+	#[test]
+	fn ttl_entries_survive_reload_with_remaining_lifetime() {
+		use std::time::Duration;
+
+		let temp_file = NamedTempFile::new().unwrap();
+
+		let datastore = JsonFileDatastore::<Vec<u8>>::new(temp_file.path()).unwrap();
+		datastore.put_with_ttl("soon".into(), vec![9], Duration::from_secs(3600)).unwrap();
+		datastore.flush().unwrap();
+		drop(datastore);
+
+		let reload = JsonFileDatastore::<Vec<u8>>::new(temp_file.path()).unwrap();
+		assert_eq!(reload.get("soon").unwrap(), &[9]);
+	}
+
+	#[test]
+	fn quota_evicts_soonest_to_expire_entry_to_make_room() {
+		use Quota;
+		use std::time::Duration;
+
+		let temp_file = NamedTempFile::new().unwrap();
+		let quota = Quota { max_entries: Some(1), max_bytes: None };
+
+		let datastore = JsonFileDatastore::<Vec<u8>>::with_quota(temp_file.path(), quota).unwrap();
+		datastore.put_with_ttl("a".into(), vec![1], Duration::from_secs(3600)).unwrap();
+		datastore.put_with_ttl("b".into(), vec![2], Duration::from_secs(7200)).unwrap();
+
+		assert_eq!(datastore.has("a"), false);
+		assert_eq!(datastore.get("b").unwrap(), &[2]);
+	}
+
+	#[test]
+	fn quota_rejects_when_nothing_is_evictable() {
+		use Quota;
+		use error::ErrorKind;
+		use std::time::Duration;
+
+		let temp_file = NamedTempFile::new().unwrap();
+		let quota = Quota { max_entries: Some(1), max_bytes: None };
+
+		let datastore = JsonFileDatastore::<Vec<u8>>::with_quota(temp_file.path(), quota).unwrap();
+		datastore.put("permanent".into(), vec![1]);
+
+		let err = datastore
+			.put_with_ttl("other".into(), vec![2], Duration::from_secs(60))
+			.unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::Quota);
+	}
+
+	#[test]
+	fn quota_bytes_accounting_survives_failed_replace_of_existing_key() {
+		use Quota;
+		use error::ErrorKind;
+		use std::time::Duration;
+
+		let temp_file = NamedTempFile::new().unwrap();
+		// "k" (`[1,2,3]`) serializes to 7 bytes, leaving 3 bytes of headroom under the quota.
+		let quota = Quota { max_entries: None, max_bytes: Some(10) };
+
+		let datastore = JsonFileDatastore::<Vec<u8>>::with_quota(temp_file.path(), quota).unwrap();
+		datastore.put("k".into(), vec![1, 2, 3]);
+
+		// Nothing else exists to evict ("k" is the only entry, and it is non-expiring), so this replace of
+		// "k" with an oversized value must fail without corrupting `total_bytes` accounting.
+		let err = datastore
+			.put_with_ttl("k".into(), vec![0u8; 64], Duration::from_secs(60))
+			.unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::Quota);
+		assert_eq!(datastore.get("k").unwrap(), &[1, 2, 3]);
+
+		// `[1,2]` serializes to 5 bytes, which fits alongside "k"'s real 7 bytes under neither
+		// quota (12 > 10) — but would wrongly fit if "k"'s size had been dropped from
+		// `total_bytes` by the failed replace above.
+		let err = datastore.put_with_ttl("other".into(), vec![1, 2], Duration::from_secs(60)).unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::Quota);
+	}
 }
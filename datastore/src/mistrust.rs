@@ -0,0 +1,127 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Filesystem trust checks performed before a datastore is allowed to load untrusted content.
+
+use error::{DatastoreError, ErrorKind, Resource};
+use std::path::Path;
+
+/// Name of the environment variable that, when set to `1`, disables the permission checks
+/// performed by `Mistrust`. Intended as an escape hatch for CI environments that run as root
+/// with a loose umask and have no meaningful notion of "another user".
+pub const TRUST_PERMISSIONS_ENV: &str = "DATASTORE_TRUST_PERMISSIONS";
+
+/// Describes how strictly `JsonFileDatastore::with_permission_checks` should verify the
+/// ownership and mode bits of the store's file and its ancestor directories.
+///
+/// On non-Unix platforms, or when disabled via [`Mistrust::ignore_checks`] /
+/// `DATASTORE_TRUST_PERMISSIONS=1`, every check is a no-op.
+#[derive(Debug, Clone)]
+pub struct Mistrust {
+	ignore_checks: bool,
+}
+
+impl Mistrust {
+	/// Builds a `Mistrust` that enforces the checks, unless the
+	/// `DATASTORE_TRUST_PERMISSIONS` environment variable is set to `1`.
+	pub fn new() -> Mistrust {
+		let ignore_checks = ::std::env::var(TRUST_PERMISSIONS_ENV)
+			.map(|v| v == "1")
+			.unwrap_or(false);
+		Mistrust { ignore_checks: ignore_checks }
+	}
+
+	/// Builds a `Mistrust` that never performs any check. Useful for tests or environments that
+	/// are known in advance not to care about filesystem permissions.
+	pub fn ignore_checks() -> Mistrust {
+		Mistrust { ignore_checks: true }
+	}
+
+	/// Walks `path` and all of its ancestors, making sure none of them is owned by a user other
+	/// than the current one or root (existing components only; ones that don't exist yet are
+	/// skipped). `path` itself and its parent directory are checked for group/world read, write,
+	/// and execute bits, since anyone able to read or traverse into them could see or tamper with
+	/// the content; directories further up are checked for group/world **write** bits only, so an
+	/// ordinary `755` directory higher up the tree is accepted.
+	pub fn check_path(&self, path: &Path) -> Result<(), DatastoreError> {
+		if self.ignore_checks {
+			return Ok(());
+		}
+
+		imp::check_path(path)
+	}
+}
+
+impl Default for Mistrust {
+	fn default() -> Mistrust {
+		Mistrust::new()
+	}
+}
+
+#[cfg(unix)]
+mod imp {
+	use super::*;
+	use std::os::unix::fs::MetadataExt;
+
+	pub fn check_path(path: &Path) -> Result<(), DatastoreError> {
+		let current_uid = unsafe { ::libc::getuid() };
+
+		for (depth, ancestor) in path.ancestors().enumerate() {
+			let metadata = match ancestor.metadata() {
+				Ok(metadata) => metadata,
+				// An ancestor that doesn't exist yet (or isn't accessible) can't leak anything.
+				Err(_) => continue,
+			};
+
+			if metadata.uid() != current_uid && metadata.uid() != 0 {
+				return Err(DatastoreError::new(
+					Resource::File { path: ancestor.to_path_buf() },
+					ErrorKind::Permission,
+				));
+			}
+
+			// `path` itself (depth 0) and its parent directory (depth 1) are checked against the
+			// full group/world rwx mask; every ancestor directory above them only needs the write
+			// bits checked, since a world- or group-readable/-executable directory further up is
+			// ordinary and not, by itself, a way to tamper with `path`'s content.
+			let mask = if depth <= 1 { 0o077 } else { 0o022 };
+			let mode = metadata.mode();
+			if mode & mask != 0 {
+				return Err(DatastoreError::new(
+					Resource::File { path: ancestor.to_path_buf() },
+					ErrorKind::Permission,
+				));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(not(unix))]
+mod imp {
+	use super::*;
+
+	/// Non-Unix platforms have no portable notion of group/world mode bits, so the check is a
+	/// no-op.
+	pub fn check_path(_path: &Path) -> Result<(), DatastoreError> {
+		Ok(())
+	}
+}
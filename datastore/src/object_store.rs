@@ -0,0 +1,169 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A [`Backend`] for path-style remote object stores (S3 and its many lookalikes).
+//!
+//! This crate has no opinion on which SDK talks to the bucket: [`RemoteBackend`] is generic over
+//! an [`ObjectStoreClient`], a small trait that any provider's client can be adapted to. This
+//! keeps `datastore` free of a hard dependency on a particular cloud SDK while still letting
+//! `JsonFileDatastore` run unmodified against a bucket, a config switch away from local files.
+
+use backend::Backend;
+use error::DatastoreError;
+
+/// A path-style client for a remote object store: get/put/delete a single object by key, and list
+/// the keys under a prefix. `RemoteBackend` adapts this to the crate's [`Backend`] trait.
+pub trait ObjectStoreClient: Send + Sync {
+	/// Fetches the object at `key`, or `None` if it doesn't exist.
+	fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DatastoreError>;
+
+	/// Stores `bytes` at `key`, replacing any previous content.
+	fn put(&self, key: &str, bytes: &[u8]) -> Result<(), DatastoreError>;
+
+	/// Deletes the object at `key`. Deleting a key that doesn't exist is not an error.
+	fn delete(&self, key: &str) -> Result<(), DatastoreError>;
+
+	/// Lists every key stored under `prefix`.
+	fn list(&self, prefix: &str) -> Result<Vec<String>, DatastoreError>;
+}
+
+/// A [`Backend`] that stores every object at `<prefix><key>` in a remote object store, reached
+/// through an [`ObjectStoreClient`].
+///
+/// `prefix` lets several datastores share a single bucket (e.g. one prefix per peer or per
+/// network) without their key listings colliding.
+pub struct RemoteBackend<C> {
+	client: C,
+	prefix: String,
+}
+
+impl<C> RemoteBackend<C>
+	where C: ObjectStoreClient
+{
+	/// Builds a backend that stores objects under `prefix` within `client`'s bucket.
+	pub fn new(client: C, prefix: String) -> RemoteBackend<C> {
+		RemoteBackend { client: client, prefix: prefix }
+	}
+
+	fn full_key(&self, key: &str) -> String {
+		format!("{}{}", self.prefix, key)
+	}
+}
+
+impl<C> Backend for RemoteBackend<C>
+	where C: ObjectStoreClient
+{
+	fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, DatastoreError> {
+		self.client.get(&self.full_key(key))
+	}
+
+	fn put_object(&self, key: &str, bytes: &[u8]) -> Result<(), DatastoreError> {
+		self.client.put(&self.full_key(key), bytes)
+	}
+
+	fn delete_object(&self, key: &str) -> Result<(), DatastoreError> {
+		self.client.delete(&self.full_key(key))
+	}
+
+	fn list(&self, prefix: &str) -> Result<Vec<String>, DatastoreError> {
+		let full_prefix = self.full_key(prefix);
+		let keys = self.client.list(&full_prefix)?;
+		Ok(
+			keys.into_iter()
+				.filter_map(|key| {
+					if key.starts_with(&self.prefix) {
+						Some(key[self.prefix.len()..].to_owned())
+					} else {
+						None
+					}
+				})
+				.collect(),
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use parking_lot::Mutex;
+	use std::collections::BTreeMap;
+
+	/// A fake `ObjectStoreClient` backed by an in-memory map, for exercising `RemoteBackend`
+	/// without a real bucket.
+	struct FakeClient {
+		objects: Mutex<BTreeMap<String, Vec<u8>>>,
+	}
+
+	impl FakeClient {
+		fn new() -> FakeClient {
+			FakeClient { objects: Mutex::new(BTreeMap::new()) }
+		}
+	}
+
+	impl ObjectStoreClient for FakeClient {
+		fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DatastoreError> {
+			Ok(self.objects.lock().get(key).cloned())
+		}
+
+		fn put(&self, key: &str, bytes: &[u8]) -> Result<(), DatastoreError> {
+			self.objects.lock().insert(key.to_owned(), bytes.to_vec());
+			Ok(())
+		}
+
+		fn delete(&self, key: &str) -> Result<(), DatastoreError> {
+			self.objects.lock().remove(key);
+			Ok(())
+		}
+
+		fn list(&self, prefix: &str) -> Result<Vec<String>, DatastoreError> {
+			Ok(self.objects.lock().keys().filter(|key| key.starts_with(prefix)).cloned().collect())
+		}
+	}
+
+	#[test]
+	fn get_put_delete_are_namespaced_under_prefix() {
+		let backend = RemoteBackend::new(FakeClient::new(), "peer-a/".to_owned());
+
+		assert_eq!(backend.get_object("index").unwrap(), None);
+
+		backend.put_object("index", b"hello").unwrap();
+		assert_eq!(backend.get_object("index").unwrap(), Some(b"hello".to_vec()));
+		// The underlying client sees the fully-qualified key, not the bare one.
+		assert_eq!(backend.client.get("peer-a/index").unwrap(), Some(b"hello".to_vec()));
+
+		backend.delete_object("index").unwrap();
+		assert_eq!(backend.get_object("index").unwrap(), None);
+	}
+
+	#[test]
+	fn list_strips_the_backend_prefix_and_ignores_other_prefixes() {
+		let client = FakeClient::new();
+		client.put("peer-a/blobs/one", b"1").unwrap();
+		client.put("peer-a/blobs/two", b"2").unwrap();
+		// Belongs to a different logical datastore sharing the same bucket.
+		client.put("peer-b/blobs/three", b"3").unwrap();
+
+		let backend = RemoteBackend::new(client, "peer-a/".to_owned());
+
+		let mut keys = backend.list("blobs/").unwrap();
+		keys.sort();
+		assert_eq!(keys, vec!["blobs/one".to_owned(), "blobs/two".to_owned()]);
+	}
+}